@@ -21,19 +21,27 @@
 //!
 
 use std::io;
+use std::io::Read as _;
 
-// use hashes::{Hash, HashEngine};
-// use hash_types::BlockHash;
+use hashes::{sha256d, Hash, HashEngine};
+use hash_types::BlockHash;
 // use util::uint::Uint256;
 use consensus::encode::{self, CompressedScript, VarInt, VarInt2, Encodable, Decodable, ReadExt, Error};
-// use network::constants::Network;
-// use blockdata::transaction::Transaction;
-// use blockdata::script;
+use network::constants::Network;
+use blockdata::block::Block;
+use blockdata::transaction::{OutPoint, Transaction};
+use blockdata::script;
+use util::address::Address;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize};
+#[cfg(feature = "serde")]
+use serde::ser::{SerializeStruct, SerializeSeq};
 
 /// A Bitcoin undo block, which is a collection of undo transactions, which
 /// themeselves are 
 #[derive(PartialEq, Eq, Clone, Debug)]
-// #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BlockUndo {
     /// List of undo transaction records, one for each transaction in the block
     /// excluding the coinbase transaction
@@ -51,11 +59,141 @@ impl BlockUndo {
         tx_count_len + txs_size
     }
 
+    /// Reconstructs every output this block's transactions spent, pairing
+    /// each with the `OutPoint` it was spent from. Errors on a transaction
+    /// or input count mismatch between `block` and this undo data.
+    pub fn spent_coins(&self, block: &Block) -> Result<Vec<(OutPoint, SpentCoin)>, encode::Error> {
+        let txdata = &block.txdata;
+        if txdata.is_empty() {
+            return Err(encode::Error::ParseFailed("block has no transactions"));
+        }
+        // The coinbase transaction has no prevouts, so undo data only
+        // covers the transactions after it.
+        let non_coinbase = &txdata[1..];
+        if non_coinbase.len() != self.txdata_undo.len() {
+            return Err(encode::Error::ParseFailed("block and undo data have a different number of transactions"));
+        }
+
+        let mut coins = Vec::new();
+        for (tx, tx_undo) in non_coinbase.iter().zip(self.txdata_undo.iter()) {
+            if tx.input.len() != tx_undo.output_undo.len() {
+                return Err(encode::Error::ParseFailed("transaction and undo data have a different number of inputs"));
+            }
+            for (input, out_undo) in tx.input.iter().zip(tx_undo.output_undo.iter()) {
+                coins.push((input.previous_output, SpentCoin {
+                    amount: out_undo.amount,
+                    script_pubkey: out_undo.script_pubkey.decompress(),
+                    height: out_undo.height,
+                    is_coin_base: out_undo.is_coin_base,
+                }));
+            }
+        }
+        Ok(coins)
+    }
+}
+
+/// A previously-spent transaction output, reconstructed from a `TxOutUndo`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SpentCoin {
+    /// The value of the spent output, in satoshis
+    pub amount: u64,
+    /// The spent output's scriptPubkey
+    pub script_pubkey: script::Script,
+    /// The height of the block containing the spent output
+    pub height: u64,
+    /// Whether the spent output's containing transaction was a coinbase
+    pub is_coin_base: bool,
+}
+
+/// A reader over a node's on-disk `rev?????.dat` file.
+///
+/// Each record in a `rev*.dat` file frames a [`BlockUndo`] with the 4-byte
+/// network magic it was written under, a 4-byte little-endian payload size,
+/// the serialized `BlockUndo` itself, and a trailing double-SHA256 checksum.
+/// Following Core's `UndoWriteToDisk`, that checksum is taken over the undo'd
+/// block's hash followed by the payload, binding each record to the block it
+/// undoes; so each record must be paired with its block's hash, in file
+/// order, which callers read from the matching `blk*.dat`. `RevFile` peels
+/// off this framing and yields the decoded records one at a time.
+pub struct RevFile<R, H> {
+    reader: R,
+    block_hashes: H,
+}
+
+impl<R: io::Read, H: Iterator<Item = BlockHash>> RevFile<R, H> {
+    /// Wraps a reader positioned at the start of a `rev*.dat` file, paired
+    /// with the hashes of the blocks its records undo, in the same order.
+    pub fn new(reader: R, block_hashes: H) -> Self {
+        RevFile { reader: reader, block_hashes: block_hashes }
+    }
+
+    fn read_record(&mut self) -> Result<Option<([u8; 4], BlockUndo)>, encode::Error> {
+        let mut magic = [0u8; 4];
+        match read_to_fill(&mut self.reader, &mut magic) {
+            Ok(true) => {}
+            Ok(false) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let block_hash = self.block_hashes.next()
+            .ok_or(encode::Error::ParseFailed("rev file has more records than block hashes"))?;
+
+        let size = u32::consensus_decode(&mut self.reader)? as usize;
+        if size > encode::MAX_VEC_SIZE {
+            return Err(encode::Error::ParseFailed("rev file record size exceeds max allocation"));
+        }
+
+        let mut payload = vec![0u8; size];
+        self.reader.read_exact(&mut payload)?;
+
+        let mut checksum = [0u8; 32];
+        self.reader.read_exact(&mut checksum)?;
+
+        let mut engine = sha256d::Hash::engine();
+        block_hash.consensus_encode(&mut engine)?;
+        engine.input(&payload);
+        let expected = sha256d::Hash::from_engine(engine).into_inner();
+        if expected != checksum {
+            return Err(encode::Error::ParseFailed("rev file checksum mismatch"));
+        }
+
+        let block_undo = BlockUndo::consensus_decode(&mut io::Cursor::new(payload))?;
+        Ok(Some((magic, block_undo)))
+    }
+}
+
+impl<R: io::Read, H: Iterator<Item = BlockHash>> Iterator for RevFile<R, H> {
+    type Item = Result<([u8; 4], BlockUndo), encode::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Fills `buf` from `r`, returning `Ok(false)` if the reader was already at
+/// EOF and `Ok(true)` once `buf` is fully populated. Used so `RevFile` can
+/// tell "no more records" apart from a record truncated partway through.
+fn read_to_fill<R: io::Read>(r: &mut R, buf: &mut [u8]) -> Result<bool, io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
 }
 
 /// A Bitcoin undo transaction, which is the reverse of a bitcoin transaction
 #[derive(PartialEq, Eq, Clone, Debug)]
-// #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TxUndo {
     /// List of undo transaction input records, one for each utxo from the original transaction
     pub output_undo: Vec<TxOutUndo>
@@ -66,14 +204,17 @@ impl_consensus_encoding!(TxUndo, output_undo);
 impl TxUndo {
     /// Get the size of the spent transaction
     pub fn get_size(&self) -> usize {
-        todo!();
+        // The size of the varint with the spent output count + the spent outputs themselves
+        let output_count_len = VarInt(self.output_undo.len() as u64).len();
+        let outputs_size: usize = self.output_undo.iter().map(TxOutUndo::get_size).sum();
+        output_count_len + outputs_size
     }
 
 }
 
 /// A Bitcoin undo transaction input, which is the reverse of a transaction input
 #[derive(PartialEq, Eq, Clone, Debug)]
-// #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TxOutUndo {
     /// Whether the spent output was a coinbase
     pub is_coin_base: bool,
@@ -90,62 +231,112 @@ pub struct TxOutUndo {
 impl Encodable for TxOutUndo {
     fn consensus_encode<S: io::Write>(
         &self,
-        s: S,
+        mut s: S,
     ) -> Result<usize, encode::Error> {
-        // let len = self.txid.consensus_encode(&mut s)?;
-        Ok(self.is_coin_base.consensus_encode(s)?)
-        // todo!();
+        // Mirrors Bitcoin Core's `TxInUndoFormatter`: the height code packs the
+        // coinbase flag into its low bit, and the reserved compatibility byte
+        // (formerly a transaction version) is only present for heights above
+        // the genesis coinbase.
+        let height_code = self.height * 2 + if self.is_coin_base { 1 } else { 0 };
+        let mut len = VarInt2(height_code).consensus_encode(&mut s)?;
+        if self.height > 0 {
+            len += 0x00u8.consensus_encode(&mut s)?;
+        }
+        let amount_compressed = compress_txout_amt(self.amount);
+        len += VarInt2(amount_compressed).consensus_encode(&mut s)?;
+        len += self.script_pubkey.consensus_encode(&mut s)?;
+        Ok(len)
     }
 }
 
 impl Decodable for TxOutUndo {
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
         // read height code, is (2 * (actual height) ) (+1 if coinbase)
-        // let mut reader = Cursor::new(values);
         let height_code = VarInt2::consensus_decode(&mut d)?.0 as usize;
-        let mut is_coin_base = false;
-        if height_code % 2 == 1 {
-            is_coin_base = true;
-        }
+        let is_coin_base = height_code % 2 == 1;
         let height = (height_code / 2) as usize;
-        // println!("found height: {}", height);
-
-        // skip byte reserved only for backwards compatibility, should always be 0x00
-        let _ = (&mut d).read_u8()?;
 
-        // 
-        let amount_compressed = VarInt2::consensus_decode(&mut d)?.0 as usize;
-        let amount = decompress_txout_amt(amount_compressed)?;
-        // println!("found amount: {}", amount);
+        // the reserved compatibility byte (formerly a transaction version) is
+        // only present for heights above the genesis coinbase, matching the
+        // layout `TxInUndoFormatter` writes on encode
+        if height > 0 {
+            let _ = (&mut d).read_u8()?;
+        }
 
-        let script_len_code = VarInt2::consensus_decode(&mut d)?.0 as usize;
-        let script_len = match script_len_code {
-            0 | 1 => 20,
-            2..=5 => 32,
-            _ => script_len_code - 6
-        };
-        // println!("found script_len {}", script_len);
-        // let mut script_pubkey_buf = Vec::with_capacity(script_len as usize);
-        let mut script_pubkey_buf = vec![0u8; script_len + 1 as usize];
-        script_pubkey_buf[0] = script_len_code as u8;
-        // d.read_slice(&mut script_pubkey_buf)?;
-        // let script_byte = (&mut d).read_u8()?;
-        (&mut d).read_slice(&mut script_pubkey_buf[1..])?;
-        // println!("found script_pubkey_buf {:?}", script_pubkey_buf);
-        let script_pubkey = CompressedScript::consensus_decode(&mut std::io::Cursor::new(script_pubkey_buf)).unwrap();
+        let (amount, script_pubkey) = decode_amount_and_script(&mut d)?;
         Ok(TxOutUndo {
             is_coin_base: is_coin_base,
             height: height as u64,
-            amount: amount as u64,
+            amount: amount,
             script_pubkey: script_pubkey,
         })
     }
 }
 
+/// Decodes the amount and scriptPubkey tail shared by both the modern and
+/// legacy `TxOutUndo` layouts: a compressed-amount `VarInt` followed by a
+/// `CompressedScript`.
+fn decode_amount_and_script<D: io::Read>(mut d: D) -> Result<(u64, CompressedScript), encode::Error> {
+    let amount_compressed = VarInt2::consensus_decode(&mut d)?.0 as usize;
+    let amount = decompress_txout_amt(amount_compressed)?;
+
+    let script_len_code = VarInt2::consensus_decode(&mut d)?.0 as usize;
+    let script_len = match script_len_code {
+        0 | 1 => 20,
+        2..=5 => 32,
+        _ => script_len_code - 6
+    };
+    if script_len > encode::MAX_VEC_SIZE {
+        return Err(encode::Error::ParseFailed("rev file script length exceeds max allocation"));
+    }
+    let mut script_pubkey_buf = vec![0u8; script_len + 1 as usize];
+    script_pubkey_buf[0] = script_len_code as u8;
+    (&mut d).read_slice(&mut script_pubkey_buf[1..])?;
+    let script_pubkey = CompressedScript::consensus_decode(&mut std::io::Cursor::new(script_pubkey_buf))?;
+    Ok((amount as u64, script_pubkey))
+}
+
+impl TxOutUndo {
+    /// Decodes a `TxOutUndo` from an archival, pre-0.8-era `rev*.dat` record,
+    /// where the byte modern nodes write as a dummy `0x00` instead holds the
+    /// spending transaction's version, as a `VarInt`. Present only when
+    /// `height` is greater than zero, same as the modern format's dummy byte.
+    pub fn consensus_decode_with_version<D: io::Read>(mut d: D) -> Result<(Self, Option<u64>), encode::Error> {
+        let height_code = VarInt2::consensus_decode(&mut d)?.0 as usize;
+        let is_coin_base = height_code % 2 == 1;
+        let height = (height_code / 2) as usize;
+
+        let legacy_version = if height > 0 {
+            Some(VarInt2::consensus_decode(&mut d)?.0)
+        } else {
+            None
+        };
+
+        let (amount, script_pubkey) = decode_amount_and_script(&mut d)?;
+        Ok((TxOutUndo {
+            is_coin_base: is_coin_base,
+            height: height as u64,
+            amount: amount,
+            script_pubkey: script_pubkey,
+        }, legacy_version))
+    }
+}
+
 impl TxOutUndo {
     /// Get the size of the spent txout
     pub fn get_size(&self) -> usize {
-        todo!();
+        // The height code varint + the reserved byte (only above height 0) +
+        // the compressed amount varint + the compressed script.
+        let height_code = self.height * 2 + if self.is_coin_base { 1 } else { 0 };
+        let mut size = VarInt2(height_code).len();
+        if self.height > 0 {
+            size += 1;
+        }
+        let amount_compressed = compress_txout_amt(self.amount);
+        size += VarInt2(amount_compressed).len();
+        size += self.script_pubkey.consensus_encode(io::sink())
+            .expect("writing to a sink never fails");
+        size
     }
 
 }
@@ -188,3 +379,410 @@ fn decompress_txout_amt(mut value_compressed: usize) -> Result<usize, Error> {
     // Apply the exponent.
     return Ok(n * 10usize.pow(exponent as u32))
 }
+
+/// Inverse of `decompress_txout_amt`, needed so `TxOutUndo::consensus_encode`
+/// can re-derive the compressed amount Core stores on disk.
+fn compress_txout_amt(mut n: u64) -> u64 {
+    // (this function mirrors Bitcoin Core's `CTxOutCompressor::CompressAmount`)
+    // No need to do any work if it's zero.
+    if n == 0 {
+        return 0;
+    }
+
+    // Factor out trailing decimal zeroes into an exponent, capped at 9 since
+    // the encoding stores the exponent as a single decimal digit.
+    let mut e = 0;
+    while n % 10 == 0 && e < 9 {
+        n /= 10;
+        e += 1;
+    }
+
+    if e < 9 {
+        // n's last digit is now in 1..=9; fold it (minus one) into the low
+        // end of the encoding alongside the remaining digits of n.
+        let d = n % 10;
+        n /= 10;
+        1 + 10 * (9 * n + (d - 1)) + e
+    } else {
+        // We've already pulled out 9 trailing zeroes, so the remaining value
+        // encodes directly with a fixed marker exponent of 9.
+        1 + 10 * (n - 1) + 9
+    }
+}
+
+/// A resolved previous output: a [`TxOutUndo`] decompressed and, where the
+/// scriptPubkey is a standard type, decoded into an [`Address`].
+#[cfg(feature = "serde")]
+#[derive(Serialize, Clone, Debug)]
+pub struct ResolvedPrevout {
+    /// The value of the spent output, in satoshis
+    pub value: u64,
+    /// The spent output's scriptPubkey, in hex
+    pub script_pubkey: String,
+    /// The decoded address of the scriptPubkey, if it is a standard type
+    pub address: Option<String>,
+    /// The height of the block containing the spent output
+    pub height: u64,
+    /// Whether the spent output's containing transaction was a coinbase
+    pub coinbase: bool,
+}
+
+#[cfg(feature = "serde")]
+impl ResolvedPrevout {
+    fn from_undo(out_undo: &TxOutUndo, network: Network) -> Self {
+        let script_pubkey = out_undo.script_pubkey.decompress();
+        let address = Address::from_script(&script_pubkey, network).map(|a| a.to_string());
+        ResolvedPrevout {
+            value: out_undo.amount,
+            script_pubkey: format!("{:x}", script_pubkey),
+            address: address,
+            height: out_undo.height,
+            coinbase: out_undo.is_coin_base,
+        }
+    }
+}
+
+/// A [`Transaction`] paired with its [`TxUndo`], serializing each input with
+/// its resolved previous output plus the transaction's total fee.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug)]
+pub struct VerboseTransaction<'a> {
+    /// The transaction being described
+    pub transaction: &'a Transaction,
+    /// The undo data for this transaction's spent inputs
+    pub tx_undo: &'a TxUndo,
+    /// Which network to decode prevout addresses for
+    pub network: Network,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> VerboseTransaction<'a> {
+    /// Pairs a transaction with its undo data for address-aware JSON output.
+    pub fn new(transaction: &'a Transaction, tx_undo: &'a TxUndo, network: Network) -> Self {
+        VerboseTransaction { transaction: transaction, tx_undo: tx_undo, network: network }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'a> Serialize for VerboseTransaction<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let prevouts: Vec<ResolvedPrevout> = self.tx_undo.output_undo.iter()
+            .map(|out_undo| ResolvedPrevout::from_undo(out_undo, self.network))
+            .collect();
+
+        let input_value: u64 = prevouts.iter().map(|p| p.value).sum();
+        let output_value: u64 = self.transaction.output.iter().map(|o| o.value).sum();
+        let fee = input_value.saturating_sub(output_value);
+
+        let mut state = serializer.serialize_struct("VerboseTransaction", 3)?;
+        state.serialize_field("transaction", self.transaction)?;
+        state.serialize_field("prevouts", &prevouts)?;
+        state.serialize_field("fee", &fee)?;
+        state.end()
+    }
+}
+
+/// A [`Block`] paired with its [`BlockUndo`], serializing the coinbase
+/// transaction as-is and every other transaction as a [`VerboseTransaction`].
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug)]
+pub struct VerboseBlock<'a> {
+    /// The block being described
+    pub block: &'a Block,
+    /// The block's undo data
+    pub block_undo: &'a BlockUndo,
+    /// Which network to decode prevout addresses for
+    pub network: Network,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> VerboseBlock<'a> {
+    /// Pairs a block with its undo data for address-aware JSON output.
+    pub fn new(block: &'a Block, block_undo: &'a BlockUndo, network: Network) -> Self {
+        VerboseBlock { block: block, block_undo: block_undo, network: network }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'a> Serialize for VerboseBlock<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+
+        let txdata = &self.block.txdata;
+        if txdata.is_empty() {
+            return Err(S::Error::custom("block has no transactions"));
+        }
+        let non_coinbase = &txdata[1..];
+        if non_coinbase.len() != self.block_undo.txdata_undo.len() {
+            return Err(S::Error::custom("block and undo data have a different number of transactions"));
+        }
+
+        let mut seq = serializer.serialize_seq(Some(txdata.len()))?;
+        seq.serialize_element(&txdata[0])?;
+        for (tx, tx_undo) in non_coinbase.iter().zip(self.block_undo.txdata_undo.iter()) {
+            seq.serialize_element(&VerboseTransaction::new(tx, tx_undo, self.network))?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::encode::{deserialize, serialize};
+    use blockdata::block::BlockHeader;
+    use blockdata::transaction::TxIn;
+
+    // The Gx coordinate of the secp256k1 generator point, used below as a
+    // stand-in compressed pubkey x-coordinate for an uncompressed-pubkey
+    // (P2PK) output.
+    const GX: [u8; 32] = [
+        0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac,
+        0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+        0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9,
+        0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+    ];
+
+    // The X-coordinate of Satoshi's well-documented uncompressed pubkey from
+    // the block 9 coinbase output (spent by block 170, the first mainnet
+    // bitcoin-to-bitcoin transaction): 04 11db93e1dcdb8a016b49840f8c53bc1e
+    // b68a382e97b1482ecad7b148a6909a5c b2e0eaddfb84ccf9744464f82e160bfa9b
+    // 8b64f9d4c03f999b8643f656b412a3. Its Y-coordinate's low bit is 1, so
+    // `CompressedScript` stores it as type 5.
+    const SATOSHI_BLOCK9_PUBKEY_X: [u8; 32] = [
+        0x11, 0xdb, 0x93, 0xe1, 0xdc, 0xdb, 0x8a, 0x01,
+        0x6b, 0x49, 0x84, 0x0f, 0x8c, 0x53, 0xbc, 0x1e,
+        0xb6, 0x8a, 0x38, 0x2e, 0x97, 0xb1, 0x48, 0x2e,
+        0xca, 0xd7, 0xb1, 0x48, 0xa6, 0x90, 0x9a, 0x5c,
+    ];
+
+    #[test]
+    fn txoutundo_roundtrip_spent_early_coinbase() {
+        // A rev*.dat record for the block 9 coinbase output, as it would be
+        // found spent by block 170: height code 19 (height 9, coinbase),
+        // reserved 0x00 byte, compressed amount 50 (== 50 BTC), then the
+        // uncompressed-pubkey CompressedScript (type 5, 32-byte X) for
+        // Satoshi's real scriptPubkey from that output.
+        let mut bytes = vec![0x13, 0x00, 0x32, 0x05];
+        bytes.extend_from_slice(&SATOSHI_BLOCK9_PUBKEY_X);
+
+        let undo: TxOutUndo = deserialize(&bytes).unwrap();
+        assert_eq!(undo.height, 9);
+        assert!(undo.is_coin_base);
+        assert_eq!(undo.amount, 50_0000_0000);
+
+        assert_eq!(serialize(&undo), bytes);
+    }
+
+    #[test]
+    fn txoutundo_roundtrip_genesis_height_omits_reserved_byte() {
+        // Height 0 must NOT carry the reserved compatibility byte.
+        let mut bytes = vec![0x00, 0x00, 0x04];
+        bytes.extend_from_slice(&GX);
+
+        let undo: TxOutUndo = deserialize(&bytes).unwrap();
+        assert_eq!(undo.height, 0);
+        assert!(!undo.is_coin_base);
+
+        assert_eq!(serialize(&undo), bytes);
+    }
+
+    fn rev_record(magic: [u8; 4], block_hash: BlockHash, payload: &[u8]) -> Vec<u8> {
+        let mut engine = sha256d::Hash::engine();
+        block_hash.consensus_encode(&mut engine).unwrap();
+        engine.input(payload);
+        let checksum = sha256d::Hash::from_engine(engine).into_inner();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&magic);
+        bytes.extend_from_slice(&serialize(&(payload.len() as u32)));
+        bytes.extend_from_slice(payload);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    #[test]
+    fn revfile_reads_a_clean_multi_record_file() {
+        let magic = [0xf9, 0xbe, 0xb4, 0xd9];
+        let hash_a = BlockHash::hash(b"block a");
+        let hash_b = BlockHash::hash(b"block b");
+        let undo_a = BlockUndo { txdata_undo: vec![] };
+        let undo_b = BlockUndo { txdata_undo: vec![TxUndo { output_undo: vec![] }] };
+
+        let mut bytes = rev_record(magic, hash_a, &serialize(&undo_a));
+        bytes.extend(rev_record(magic, hash_b, &serialize(&undo_b)));
+
+        let rev_file = RevFile::new(io::Cursor::new(bytes), vec![hash_a, hash_b].into_iter());
+        let records: Vec<_> = rev_file.collect::<Result<_, _>>().unwrap();
+        assert_eq!(records, vec![(magic, undo_a), (magic, undo_b)]);
+    }
+
+    #[test]
+    fn revfile_errors_on_truncated_record() {
+        let magic = [0xf9, 0xbe, 0xb4, 0xd9];
+        let hash = BlockHash::hash(b"block a");
+        let undo = BlockUndo { txdata_undo: vec![] };
+        let mut bytes = rev_record(magic, hash, &serialize(&undo));
+        // Cut the record off partway through the checksum.
+        bytes.truncate(bytes.len() - 4);
+
+        let mut rev_file = RevFile::new(io::Cursor::new(bytes), vec![hash].into_iter());
+        match rev_file.next() {
+            Some(Err(_)) => {}
+            other => panic!("expected a truncated-record error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn revfile_errors_on_checksum_mismatch() {
+        let magic = [0xf9, 0xbe, 0xb4, 0xd9];
+        let hash = BlockHash::hash(b"block a");
+        let undo = BlockUndo { txdata_undo: vec![] };
+        let mut bytes = rev_record(magic, hash, &serialize(&undo));
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let mut rev_file = RevFile::new(io::Cursor::new(bytes), vec![hash].into_iter());
+        match rev_file.next() {
+            Some(Err(encode::Error::ParseFailed(msg))) => assert_eq!(msg, "rev file checksum mismatch"),
+            other => panic!("expected a checksum mismatch error, got {:?}", other),
+        }
+    }
+
+    fn dummy_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        }
+    }
+
+    fn dummy_tx(input_count: usize) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: (0..input_count).map(|_| TxIn::default()).collect(),
+            output: vec![],
+        }
+    }
+
+    fn dummy_out_undo() -> TxOutUndo {
+        let mut script_bytes = vec![0x04];
+        script_bytes.extend_from_slice(&GX);
+        TxOutUndo {
+            is_coin_base: false,
+            height: 100,
+            amount: 1000,
+            script_pubkey: CompressedScript::consensus_decode(&mut io::Cursor::new(script_bytes)).unwrap(),
+        }
+    }
+
+    #[test]
+    fn spent_coins_errors_on_empty_block() {
+        let block = Block { header: dummy_header(), txdata: vec![] };
+        let block_undo = BlockUndo { txdata_undo: vec![] };
+        assert!(block_undo.spent_coins(&block).is_err());
+    }
+
+    #[test]
+    fn spent_coins_errors_on_transaction_count_mismatch() {
+        let block = Block { header: dummy_header(), txdata: vec![dummy_tx(0), dummy_tx(1)] };
+        // The block has one non-coinbase transaction, but there's no undo data for it.
+        let block_undo = BlockUndo { txdata_undo: vec![] };
+        assert!(block_undo.spent_coins(&block).is_err());
+    }
+
+    #[test]
+    fn spent_coins_errors_on_input_count_mismatch() {
+        let block = Block { header: dummy_header(), txdata: vec![dummy_tx(0), dummy_tx(2)] };
+        // The one non-coinbase transaction has two inputs, but only one undo entry.
+        let block_undo = BlockUndo { txdata_undo: vec![TxUndo { output_undo: vec![dummy_out_undo()] }] };
+        assert!(block_undo.spent_coins(&block).is_err());
+    }
+
+    #[test]
+    fn spent_coins_joins_block_and_undo_data() {
+        let block = Block { header: dummy_header(), txdata: vec![dummy_tx(0), dummy_tx(1)] };
+        let block_undo = BlockUndo { txdata_undo: vec![TxUndo { output_undo: vec![dummy_out_undo()] }] };
+
+        let coins = block_undo.spent_coins(&block).unwrap();
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].0, OutPoint::default());
+        assert_eq!(coins[0].1.amount, 1000);
+        assert_eq!(coins[0].1.height, 100);
+        assert!(!coins[0].1.is_coin_base);
+    }
+
+    #[test]
+    fn get_size_matches_serialized_len() {
+        let out_undo_at_height_0 = TxOutUndo {
+            is_coin_base: false,
+            height: 0,
+            amount: 0,
+            script_pubkey: dummy_out_undo().script_pubkey,
+        };
+        let out_undo_above_height_0 = dummy_out_undo();
+
+        for out_undo in &[out_undo_at_height_0, out_undo_above_height_0] {
+            assert_eq!(out_undo.get_size(), serialize(out_undo).len());
+        }
+
+        let tx_undo = TxUndo { output_undo: vec![dummy_out_undo(), dummy_out_undo()] };
+        assert_eq!(tx_undo.get_size(), serialize(&tx_undo).len());
+
+        let block_undo = BlockUndo { txdata_undo: vec![tx_undo] };
+        assert_eq!(block_undo.get_size(), serialize(&block_undo).len());
+    }
+
+    #[test]
+    fn legacy_decode_reads_reserved_byte_as_transaction_version() {
+        // Same height code/coinbase flag and amount/script tail as
+        // `txoutundo_roundtrip_spent_early_coinbase`, but with the reserved
+        // byte replaced by a one-byte legacy transaction version of 2.
+        let mut bytes = vec![0x13, 0x02, 0x32, 0x05];
+        bytes.extend_from_slice(&SATOSHI_BLOCK9_PUBKEY_X);
+
+        let (undo, version) = TxOutUndo::consensus_decode_with_version(&mut io::Cursor::new(bytes)).unwrap();
+        assert_eq!(undo.height, 9);
+        assert!(undo.is_coin_base);
+        assert_eq!(undo.amount, 50_0000_0000);
+        assert_eq!(version, Some(2));
+    }
+
+    #[test]
+    fn legacy_decode_omits_version_at_height_0() {
+        let mut bytes = vec![0x00, 0x00, 0x05];
+        bytes.extend_from_slice(&SATOSHI_BLOCK9_PUBKEY_X);
+
+        let (undo, version) = TxOutUndo::consensus_decode_with_version(&mut io::Cursor::new(bytes)).unwrap();
+        assert_eq!(undo.height, 0);
+        assert_eq!(version, None);
+    }
+
+    #[test]
+    fn compress_decompress_amt_is_identity() {
+        // Round numbers Core's compressor is specifically tuned for.
+        let round_numbers = [
+            0u64,
+            1,
+            1_0000_0000,       // 1 BTC
+            50_0000_0000,      // 50 BTC
+            21_000_000_0000_0000, // entire supply, in satoshis
+        ];
+        for &n in &round_numbers {
+            let compressed = compress_txout_amt(n);
+            let decompressed = decompress_txout_amt(compressed as usize).unwrap();
+            assert_eq!(decompressed as u64, n);
+        }
+
+        // A spread of arbitrary (non-round) satoshi values.
+        for n in (1u64..2_000_000).step_by(104729) {
+            let compressed = compress_txout_amt(n);
+            let decompressed = decompress_txout_amt(compressed as usize).unwrap();
+            assert_eq!(decompressed as u64, n);
+        }
+    }
+}